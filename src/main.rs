@@ -2,7 +2,10 @@
 //!
 //! **See**: [Original Link](http://www.reddit.com/r/dailyprogrammer/comments/2m82yz/20141114_challenge_188_hard_arrows_and_arrows/)
 
-use std::io::{File, BufferedReader};
+#[cfg(feature = "parallel")]
+extern crate rayon;
+
+use std::io::{File, BufferedReader, Buffer, IoError};
 use std::os;
 use std::collections::HashMap;
 
@@ -15,6 +18,26 @@ fn step(pos: uint, delta: int, dimension: uint) -> uint {
     }
 }
 
+/// Errors that can arise while parsing a `GraphMeta` or a `GraphND`.
+#[deriving(Show)]
+pub enum ParseError {
+    /// The reader held no lines at all.
+    EmptyInput,
+    /// The dimensions line held the wrong number of sizes: `GraphMeta` needs
+    /// exactly two, `GraphND` at least one.
+    BadDimensions { found: uint },
+    /// A cell glyph was not one of `^v<>`.
+    UnknownGlyph(char),
+    /// A row held the wrong number of pointers.
+    RowWidthMismatch { expected: uint, found: uint, row: uint },
+    /// The grid held the wrong number of rows.
+    HeightMismatch { expected: uint, found: uint },
+    /// An N-dimensional grid held the wrong number of cells for its shape.
+    CellCountMismatch { expected: uint, found: uint },
+    /// Underlying I/O failure.
+    Io(IoError),
+}
+
 /// An expression of direction in 2-space
 #[deriving(Show, Clone)]
 pub enum Direction {
@@ -30,13 +53,13 @@ pub enum Direction {
 
 impl Direction {
     /// Creates a new `Direction` from a symbol.
-    pub fn from_glyph(c: char) -> Direction {
+    pub fn from_glyph(c: char) -> Result<Direction, ParseError> {
         match c {
-            '^'   => Direction::Up,
-            'v'   => Direction::Down,
-            '<'   => Direction::Left,
-            '>'   => Direction::Right,
-            other => panic!("{} is not a recognizable direction", other),
+            '^'   => Ok(Direction::Up),
+            'v'   => Ok(Direction::Down),
+            '<'   => Ok(Direction::Left),
+            '>'   => Ok(Direction::Right),
+            other => Err(ParseError::UnknownGlyph(other)),
         }
     }
 
@@ -63,41 +86,74 @@ pub struct GraphMeta {
 
 impl GraphMeta {
     /// Creates a new `GraphMeta` from a file at the path *fname*.
-    pub fn from_input_file(fname: &str) -> GraphMeta {
+    ///
+    /// Delegates to `from_reader`; any malformed input surfaces as a
+    /// `ParseError` rather than a panic.
+    pub fn from_input_file(fname: &str) -> Result<GraphMeta, ParseError> {
         let path = Path::new(fname);
-        let mut file = BufferedReader::new(File::open(&path));
-        let file_lines: Vec<String> = file.lines().map(|x| x.unwrap()).collect();
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e)   => return Err(ParseError::Io(e)),
+        };
+        GraphMeta::from_reader(BufferedReader::new(file))
+    }
+
+    /// Creates a new `GraphMeta` from any buffered reader.
+    ///
+    /// Accepting any `Buffer` lets callers feed a file, stdin, or an in-memory
+    /// string, which is what makes the parser usable from another program.
+    pub fn from_reader<R: Buffer>(mut r: R) -> Result<GraphMeta, ParseError> {
+        let file_lines: Vec<String> =
+            match r.lines().collect::<Result<Vec<String>, IoError>>() {
+                Ok(lines) => lines,
+                Err(e)    => return Err(ParseError::Io(e)),
+            };
+
+        if file_lines.is_empty() {
+            return Err(ParseError::EmptyInput);
+        }
 
         // get purported bounds of grid
         let (width, height) = match file_lines[0].as_slice().trim().split(' ')
             .filter_map(from_str).collect::<Vec<uint>>().as_slice() {
             [width, height] => (width, height),
-            other => panic!("Dimensions line has {} elements when it must have 2", 
-                           other.len()),
+            other => return Err(ParseError::BadDimensions { found: other.len() }),
         };
 
         // build up grid of Directions from input
         let mut pointers = Vec::new();
-        for line in file_lines.iter().skip(1) {
-            let line_pointers: Vec<Direction> = 
-                line.as_slice().trim().chars().map(Direction::from_glyph)
-                .collect();
-            if line_pointers.len() != width {
-                panic!("Line contains {} pointers when it should contain {}", 
-                      line_pointers.len(), width);
+        for (i, line) in file_lines.iter().skip(1).enumerate() {
+            let mut row = Vec::new();
+            for c in line.as_slice().trim().chars() {
+                row.push(try!(Direction::from_glyph(c)));
             }
-            pointers.push(line_pointers);
+            if row.len() != width {
+                return Err(ParseError::RowWidthMismatch {
+                    expected: width, found: row.len(), row: i });
+            }
+            pointers.push(row);
         }
         if pointers.len() != height {
-            panic!(
-                "File contains {} lines of pointers when it should contain {}", 
-                pointers.len(), height);
+            return Err(ParseError::HeightMismatch {
+                expected: height, found: pointers.len() });
         }
 
-        GraphMeta { width: width, height: height, pointers: pointers }
+        Ok(GraphMeta { width: width, height: height, pointers: pointers })
     }
     
-    /// Finds a `Cycle` rooted at the point given by (*x*, *y*). 
+    /// Follows the pointer at (*x*, *y*) to the coordinate of its successor.
+    ///
+    /// Wraparound is modular and independent on each axis.
+    fn step_from(&self, x: uint, y: uint, pointer: Direction) -> (uint, uint) {
+        match pointer {
+            Direction::Up    => (x, step(y, -1, self.height)),
+            Direction::Down  => (x, step(y, 1, self.height)),
+            Direction::Left  => (step(x, -1, self.width), y),
+            Direction::Right => (step(x, 1, self.width), y),
+        }
+    }
+
+    /// Finds a `Cycle` rooted at the point given by (*x*, *y*).
     ///
     /// (*x*, *y*) need not be a part of the returned `Cycle`, it may simply be u
     /// a *prelude* to a cycle.
@@ -118,12 +174,7 @@ impl GraphMeta {
             let pointer = self.pointers[cur_y][cur_x];
             cycle.push(Node { x: cur_x, y: cur_y, pointer: pointer });
 
-            let (next_x, next_y) = match pointer {
-                Direction::Up    => (cur_x, step(cur_y, -1, self.height)),
-                Direction::Down  => (cur_x, step(cur_y, 1, self.height)), 
-                Direction::Left  => (step(cur_x, -1, self.width), cur_y),
-                Direction::Right => (step(cur_x, 1, self.width), cur_y),
-            };
+            let (next_x, next_y) = self.step_from(cur_x, cur_y, pointer);
 
             cur_x = next_x;
             cur_y = next_y;
@@ -141,23 +192,309 @@ impl GraphMeta {
         }
     }
 
-    /// Returns the `Cycle` of maximum length present. 
+    /// Decomposes the board into its disjoint cycles and their basins.
+    ///
+    /// A single `O(width*height)` pass colours each cell once; the length of
+    /// each walk (its tail, plus the cycle's own cells when the walk closes a
+    /// new cycle) is added to the basin of the cycle it drains into.
+    pub fn decompose(&self) -> Vec<Component> {
+        /// Per-cell colour used while walking the functional graph.
+        enum State {
+            /// Not yet reached by any walk.
+            Unvisited,
+            /// On the current walk at the given stack position.
+            OnStack(uint),
+            /// Fully explored; drains into the `Component` at this index.
+            Done(uint),
+        }
+
+        let size = self.width * self.height;
+        let mut state = Vec::from_fn(size, |_| State::Unvisited);
+        let mut components: Vec<Component> = Vec::new();
+
+        for start in range(0, size) {
+            match state[start] {
+                State::Unvisited => {}
+                _ => continue,
+            }
+
+            let mut path: Cycle = Vec::new();
+            let mut cur_x = start % self.width;
+            let mut cur_y = start / self.width;
+            let drain: uint;
+
+            loop {
+                let idx = cur_y * self.width + cur_x;
+                match state[idx] {
+                    State::OnStack(j) => {
+                        // closed a brand-new cycle: path[j..]
+                        drain = components.len();
+                        let cycle: Cycle = path.slice_from(&j).to_vec();
+                        components.push(Component { cycle: cycle, basin: 0u });
+                        break;
+                    }
+                    // merged into already-explored territory; no new cycle
+                    State::Done(id) => {
+                        drain = id;
+                        break;
+                    }
+                    State::Unvisited => {
+                        *state.get_mut(idx) = State::OnStack(path.len());
+                        let pointer = self.pointers[cur_y][cur_x];
+                        path.push(Node { x: cur_x, y: cur_y, pointer: pointer });
+                        let (next_x, next_y) =
+                            self.step_from(cur_x, cur_y, pointer);
+                        cur_x = next_x;
+                        cur_y = next_y;
+                    }
+                }
+            }
+
+            // every cell of this walk drains into `drain`
+            components.get_mut(drain).basin += path.len();
+            for node in path.iter() {
+                *state.get_mut(node.y * self.width + node.x) =
+                    State::Done(drain);
+            }
+        }
+
+        components
+    }
+
+    /// Root key of a `Cycle`: the flat index `y*width + x` of its cell nearest
+    /// to (*0*, *0*). Used to break length ties deterministically.
+    fn cycle_root(&self, cycle: &Cycle) -> uint {
+        cycle.iter().map(|n| n.y * self.width + n.x).min().unwrap()
+    }
+
+    /// Returns `true` when *cand* should displace *best*: a longer cycle wins,
+    /// equal lengths break toward the smaller root key.
+    fn cycle_is_better(&self, cand: &Cycle, best: &Cycle) -> bool {
+        if cand.len() != best.len() {
+            cand.len() > best.len()
+        } else if cand.len() == 0 {
+            false
+        } else {
+            self.cycle_root(cand) < self.cycle_root(best)
+        }
+    }
+
+    /// Returns the `Cycle` of maximum length present.
     ///
     /// Ties are broken in favor of Cycles rooted by a position in the graph
-    /// closer to (*0*, *0*).
+    /// closer to (*0*, *0*). This is simply the `Component` with the longest
+    /// cycle.
+    #[cfg(not(feature = "parallel"))]
     fn get_max_cycle(&self) -> Cycle {
-        let mut max_length = 0u;
         let mut max_cycle: Cycle = Vec::new();
+        for comp in self.decompose().move_iter() {
+            if self.cycle_is_better(&comp.cycle, &max_cycle) {
+                max_cycle = comp.cycle;
+            }
+        }
+        max_cycle
+    }
+
+    /// Parallel `get_max_cycle`, enabled by the `parallel` feature.
+    ///
+    /// Reduces over the same `decompose` output as the serial path, so the
+    /// chosen `Cycle` is identical; only the max-reduction runs concurrently.
+    #[cfg(feature = "parallel")]
+    fn get_max_cycle(&self) -> Cycle {
+        use rayon::prelude::*;
+
+        self.decompose()
+            .into_par_iter()
+            .map(|comp| comp.cycle)
+            .reduce(|| Vec::new(),
+                    |a, b| if self.cycle_is_better(&b, &a) { b } else { a })
+    }
+}
+
+/// Sizes of each axis of an N-dimensional toroidal grid, most-significant axis
+/// first (axis 0 varies slowest in the flattened row-major data).
+pub type Shape = Vec<uint>;
+
+/// A cycle in an N-dimensional grid, as flattened cell indices in walk order.
+pub type CycleND = Vec<uint>;
+
+/// A direction in N-space: a single-cell step along one axis.
+///
+/// The 2-D glyphs `^v<>` are the `D = 2` specialization (see `from_glyph`).
+#[deriving(Show, Clone, PartialEq)]
+pub struct Axial {
+    /// Index of the axis this direction steps along.
+    pub axis: uint,
+    /// `true` for a `+1` step, `false` for a `-1` step; both wrap modularly.
+    pub positive: bool,
+}
+
+impl Axial {
+    /// Creates an `Axial` from a 2-D glyph — the `D = 2` specialization.
+    ///
+    /// Axis 0 is vertical (rows); axis 1 is horizontal (columns).
+    pub fn from_glyph(c: char) -> Result<Axial, ParseError> {
+        match c {
+            '^'   => Ok(Axial { axis: 0, positive: false }),
+            'v'   => Ok(Axial { axis: 0, positive: true }),
+            '<'   => Ok(Axial { axis: 1, positive: false }),
+            '>'   => Ok(Axial { axis: 1, positive: true }),
+            other => Err(ParseError::UnknownGlyph(other)),
+        }
+    }
+
+    /// Gets the 2-D glyph for this direction.
+    pub fn to_glyph(&self) -> char {
+        match (self.axis, self.positive) {
+            (0, false) => '^',
+            (0, true)  => 'v',
+            (1, false) => '<',
+            (1, true)  => '>',
+            _          => panic!("no glyph for axis {} in 2-space", self.axis),
+        }
+    }
+}
+
+/// N-dimensional generalization of `GraphMeta`.
+///
+/// A cell in a `D`-dimensional toroidal grid points along one of `2·D` unit
+/// vectors. Once cells are addressed by their flattened row-major index the
+/// cycle-finding machinery is identical to the 2-D case.
+#[deriving(Show)]
+pub struct GraphND {
+    /// Size of each axis, most-significant first.
+    pub shape: Shape,
+    /// One direction per cell, in flattened row-major order.
+    pub pointers: Vec<Axial>,
+}
+
+impl GraphND {
+    /// Parses an N-dimensional grid from *reader*.
+    ///
+    /// The first line lists the `D` axis sizes; the remaining glyphs, in
+    /// flattened row-major order, give each cell's direction. Only the `D = 2`
+    /// glyph set is currently defined (see `Axial::from_glyph`).
+    ///
+    /// Mirrors `GraphMeta::from_reader`: any `Buffer` by value, `Result` out.
+    pub fn from_reader<R: Buffer>(mut r: R) -> Result<GraphND, ParseError> {
+        let lines: Vec<String> =
+            match r.lines().collect::<Result<Vec<String>, IoError>>() {
+                Ok(lines) => lines,
+                Err(e)    => return Err(ParseError::Io(e)),
+            };
+
+        if lines.is_empty() {
+            return Err(ParseError::EmptyInput);
+        }
+
+        let shape: Shape = lines[0].as_slice().trim().split(' ')
+            .filter_map(from_str).collect();
+        if shape.len() == 0 {
+            return Err(ParseError::BadDimensions { found: 0 });
+        }
+
+        let mut pointers = Vec::new();
+        for line in lines.iter().skip(1) {
+            for c in line.as_slice().trim().chars() {
+                pointers.push(try!(Axial::from_glyph(c)));
+            }
+        }
+
+        let expected = shape.iter().fold(1u, |acc, &d| acc * d);
+        if pointers.len() != expected {
+            return Err(ParseError::CellCountMismatch {
+                expected: expected, found: pointers.len() });
+        }
+
+        Ok(GraphND { shape: shape, pointers: pointers })
+    }
+
+    /// Number of cells in the grid (product of the axis sizes).
+    pub fn size(&self) -> uint {
+        self.shape.iter().fold(1u, |acc, &d| acc * d)
+    }
+
+    /// Row-major strides for each axis (the last axis has stride 1).
+    fn strides(&self) -> Vec<uint> {
+        let mut strides = Vec::from_elem(self.shape.len(), 1u);
+        for axis in range(0, self.shape.len()).rev() {
+            if axis + 1 < self.shape.len() {
+                *strides.get_mut(axis) =
+                    strides[axis + 1] * self.shape[axis + 1];
+            }
+        }
+        strides
+    }
+
+    /// Follows the pointer at flat index *idx* to its successor's flat index.
+    ///
+    /// Wraparound is modular and independent on each axis.
+    fn step_from(&self, idx: uint, dir: Axial, strides: &[uint]) -> uint {
+        let stride = strides[dir.axis];
+        let dim = self.shape[dir.axis];
+        let coord = (idx / stride) % dim;
+        let delta = if dir.positive { 1 } else { -1 };
+        let next = step(coord, delta, dim);
+        idx - coord * stride + next * stride
+    }
+
+    /// Returns the longest `CycleND` present, as flattened cell indices.
+    ///
+    /// Ties break toward the cycle with the smallest flat index. Uses the same
+    /// single colouring pass as `GraphMeta::decompose`.
+    pub fn get_max_cycle(&self) -> CycleND {
+        /// Per-cell colour used while walking the functional graph.
+        enum State {
+            /// Not yet reached by any walk.
+            Unvisited,
+            /// On the current walk at the given stack position.
+            OnStack(uint),
+            /// Fully explored.
+            Done,
+        }
+
+        let size = self.size();
+        let strides = self.strides();
+        let mut state = Vec::from_fn(size, |_| State::Unvisited);
+        let mut max_cycle: CycleND = Vec::new();
+        let mut max_root = 0u;
+
+        for start in range(0, size) {
+            match state[start] {
+                State::Unvisited => {}
+                _ => continue,
+            }
+
+            let mut path: CycleND = Vec::new();
+            let mut cur = start;
 
-        for x in range(0, self.width) {
-            for y in range(0, self.height) {
-                let cycle = self.get_cycle_from(x, y);
-                if cycle.len() > max_length {
-                    max_length = cycle.len(); 
-                    max_cycle = cycle;
+            loop {
+                match state[cur] {
+                    State::OnStack(j) => {
+                        let cycle: CycleND = path.slice_from(&j).to_vec();
+                        let root = cycle.iter().map(|&i| i).min().unwrap();
+                        if cycle.len() > max_cycle.len()
+                            || (cycle.len() == max_cycle.len()
+                                && root < max_root) {
+                            max_root = root;
+                            max_cycle = cycle;
+                        }
+                        break;
+                    }
+                    State::Done => break,
+                    State::Unvisited => {
+                        *state.get_mut(cur) = State::OnStack(path.len());
+                        path.push(cur);
+                        cur = self.step_from(cur, self.pointers[cur], strides.as_slice());
+                    }
                 }
             }
+
+            for &i in path.iter() {
+                *state.get_mut(i) = State::Done;
+            }
         }
+
         max_cycle
     }
 }
@@ -173,6 +510,16 @@ pub struct Node {
 /// Representation of a cycle in the input graph.
 pub type Cycle = Vec<Node>;
 
+/// A single cycle together with its *basin of attraction*.
+#[deriving(Show, Clone)]
+pub struct Component {
+    /// The cycle every cell in this basin eventually enters.
+    pub cycle: Cycle,
+    /// Number of cells whose walk drains into `cycle`, including the cycle's
+    /// own cells and every tail/prelude cell feeding into it.
+    pub basin: uint,
+}
+
 /// Prints a textual representation of a cycle to *stdout*.
 fn print_cycle(cycle: &Cycle, meta: &GraphMeta) {
     let mut lines = Vec::new();
@@ -200,9 +547,86 @@ fn main() {
         return
     }
 
-    let graph_meta = GraphMeta::from_input_file(args[1].as_slice());
+    let graph_meta = match GraphMeta::from_input_file(args[1].as_slice()) {
+        Ok(graph_meta) => graph_meta,
+        Err(e) => {
+            println!("Failed to parse input: {}", e);
+            return
+        }
+    };
     let max_cycle = graph_meta.get_max_cycle();
     println!("Longest cycle: {}", max_cycle.len());
     println!("Position:");
     print_cycle(&max_cycle, &graph_meta);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{GraphMeta, GraphND, ParseError};
+    use std::io::{BufferedReader, MemReader};
+
+    /// Wraps an in-memory string as a `Buffer`, as a library caller would feed
+    /// stdin or a test fixture.
+    fn reader(s: &str) -> BufferedReader<MemReader> {
+        BufferedReader::new(MemReader::new(s.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn decompose_basins_cover_the_board() {
+        // two independent 2-cycles, one per row
+        let graph = GraphMeta::from_reader(reader("2 2\n><\n><\n")).unwrap();
+        let components = graph.decompose();
+        assert_eq!(components.len(), 2);
+        let total = components.iter().fold(0u, |acc, c| acc + c.basin);
+        assert_eq!(total, graph.width * graph.height);
+    }
+
+    #[test]
+    fn nd_finds_a_cycle_on_a_3d_shape() {
+        // 2x2x2 board, every cell stepping +1 along the last-but-one axis
+        let graph = GraphND::from_reader(reader("2 2 2\n>>>>\n>>>>\n")).unwrap();
+        assert_eq!(graph.size(), 8u);
+        assert_eq!(graph.get_max_cycle(), vec![0u, 2u]);
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        match GraphMeta::from_reader(reader("")) {
+            Err(ParseError::EmptyInput) => {}
+            other => panic!("expected EmptyInput, got {}", other),
+        }
+    }
+
+    #[test]
+    fn bad_dimensions_is_an_error() {
+        match GraphMeta::from_reader(reader("1 2 3\n")) {
+            Err(ParseError::BadDimensions { found: 3 }) => {}
+            other => panic!("expected BadDimensions, got {}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_glyph_is_an_error() {
+        match GraphMeta::from_reader(reader("1 1\nx\n")) {
+            Err(ParseError::UnknownGlyph('x')) => {}
+            other => panic!("expected UnknownGlyph, got {}", other),
+        }
+    }
+
+    #[test]
+    fn row_width_mismatch_is_an_error() {
+        match GraphMeta::from_reader(reader("2 1\n>\n")) {
+            Err(ParseError::RowWidthMismatch { expected: 2, found: 1, row: 0 })
+                => {}
+            other => panic!("expected RowWidthMismatch, got {}", other),
+        }
+    }
+
+    #[test]
+    fn height_mismatch_is_an_error() {
+        match GraphMeta::from_reader(reader("1 2\n>\n")) {
+            Err(ParseError::HeightMismatch { expected: 2, found: 1 }) => {}
+            other => panic!("expected HeightMismatch, got {}", other),
+        }
+    }
+}